@@ -0,0 +1,135 @@
+use crate::send_payment;
+use breez_sdk_core::BreezServices;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+const TOKEN_STORE_PATH: &str = "l402_tokens.json";
+
+/// Credentials earned by paying an L402 challenge, cached on disk and keyed by URL and then
+/// by macaroon/token so two distinct macaroons for the same URL never clobber each other.
+#[derive(Default, Serialize, Deserialize)]
+struct TokenStore {
+    credentials: HashMap<String, HashMap<String, String>>,
+}
+
+impl TokenStore {
+    fn load() -> Self {
+        match fs::read_to_string(TOKEN_STORE_PATH) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(self) {
+            let _ = fs::write(TOKEN_STORE_PATH, json);
+        }
+    }
+
+    /// Returns a cached `(token, credential)` pair for this URL, if any, to try optimistically.
+    fn get(&self, url: &str) -> Option<(String, String)> {
+        self.credentials
+            .get(url)?
+            .iter()
+            .next()
+            .map(|(token, credential)| (token.clone(), credential.clone()))
+    }
+
+    fn insert(&mut self, url: &str, token: &str, credential: &str) {
+        self.credentials
+            .entry(url.to_string())
+            .or_default()
+            .insert(token.to_string(), credential.to_string());
+    }
+
+    fn remove(&mut self, url: &str, token: &str) {
+        if let Some(tokens) = self.credentials.get_mut(url) {
+            tokens.remove(token);
+        }
+    }
+}
+
+/// Drive an L402-gated HTTP endpoint, paying the lightning invoice in the `WWW-Authenticate`
+/// challenge when needed and reusing the resulting `token:preimage` credential on later calls.
+pub async fn pay_and_fetch(
+    sdk: &Arc<BreezServices>,
+    url: &str,
+    method: &str,
+    body: &Value,
+) -> Result<String, String> {
+    let method = reqwest::Method::from_bytes(method.as_bytes())
+        .map_err(|_| format!("invalid http method: {}", method))?;
+    let client = reqwest::ClientBuilder::new()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut store = TokenStore::load();
+
+    if let Some((token, credential)) = store.get(url) {
+        let resp = client
+            .request(method.clone(), url)
+            .header("Authorization", format!("L402 {}", credential))
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if resp.status() != reqwest::StatusCode::PAYMENT_REQUIRED {
+            return resp.text().await.map_err(|e| e.to_string());
+        }
+
+        log::info!("Cached L402 token was rejected, re-triggering payment.");
+        store.remove(url, &token);
+    }
+
+    let resp = client
+        .request(method.clone(), url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let l402header = resp
+        .headers()
+        .get("WWW-Authenticate")
+        .ok_or("server did not return WWW-Authenticate header in 402 response.")?
+        .to_str()
+        .map_err(|e| e.to_string())?;
+
+    let re = regex::Regex::new(
+        r#"^L402 (token|macaroon)=\"(?<token>.*)\", invoice=\"(?<invoice>.*)\""#,
+    )
+    .unwrap();
+    let caps = re
+        .captures(l402header)
+        .ok_or("WWW-Authenticate header is not a valid L402")?;
+    let token = caps["token"].to_string();
+    let invoice = caps["invoice"].to_string();
+
+    log::info!(
+        "Paying lightning invoice to get access to the API: {}",
+        invoice
+    );
+    let payment = send_payment(sdk, &invoice, false).await;
+    let preimage = match payment.details {
+        breez_sdk_core::PaymentDetails::Ln { data } => data.payment_preimage,
+        _ => unreachable!(),
+    };
+
+    let credential = format!("{}:{}", token, preimage);
+    store.insert(url, &token, &credential);
+    store.save();
+
+    let resp = client
+        .request(method, url)
+        .header("Authorization", format!("L402 {}", credential))
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    resp.text().await.map_err(|e| e.to_string())
+}
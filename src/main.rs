@@ -4,18 +4,22 @@ use breez_sdk_core::ListPaymentsRequest;
 use breez_sdk_core::Payment;
 use breez_sdk_core::PaymentTypeFilter;
 use breez_sdk_core::{
-    parse, BreezEvent, BreezServices, EnvironmentType, EventListener, GreenlightNodeConfig,
-    ReceivePaymentRequest, ReceivePaymentResponse,
+    parse, BreezEvent, BreezServices, EnvironmentType, EventListener, GreenlightCredentials,
+    GreenlightNodeConfig, HealthCheckStatus, LnUrlCallbackStatus, PaymentFailureReport,
+    ReceivePaymentRequest, ReceivePaymentResponse, SendPaymentRequest,
 };
 use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use log::info;
 use serde::Serialize;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::sync::Arc;
 use std::{env, str::FromStr};
 
+mod l402;
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -29,116 +33,219 @@ async fn main() {
         })
         .init()
         .unwrap();
+    let format = if cli.json {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Text
+    };
+
     match &cli.command {
-        Commands::GenerateMnemonic => {
-            let mnemonic = Mnemonic::generate_in(Language::English, 12).unwrap();
-            info!("Generated mnemonic: {mnemonic}");
-            info!("Set the environment variable 'MNEMONIC', and run another command.");
+        Commands::GenerateMnemonic => dispatch_mnemonic(),
+        Commands::HealthCheck => dispatch_health_check().await,
+        Commands::Interactive => {
+            let sdk = connect().await;
+            run_interactive(&sdk, format).await;
         }
-        Commands::NodeInfo => {
+        // ChatGPT/Request just print a final HTTP response; there's no async event to wait
+        // on, so unlike the other commands they don't pause for a keypress afterwards.
+        command @ (Commands::ChatGPT { .. } | Commands::Request { .. }) => {
             let sdk = connect().await;
-            let node_info = sdk.node_info().unwrap();
-            info!("Node ID: {:?}", node_info.id);
-            info!("Spendable Amount: {:?}", node_info.max_payable_msat);
+            dispatch(&sdk, command, format).await;
+        }
+        command => {
+            let sdk = connect().await;
+            dispatch(&sdk, command, format).await;
             pause();
         }
+    };
+}
+
+fn dispatch_mnemonic() {
+    let mnemonic = Mnemonic::generate_in(Language::English, 12).unwrap();
+    info!("Generated mnemonic: {mnemonic}");
+    info!("Set the environment variable 'MNEMONIC', and run another command.");
+}
+
+async fn dispatch_health_check() {
+    let api_key = get_env_var("BREEZ_API_KEY").unwrap();
+    let health = BreezServices::service_health_check(api_key).await.unwrap();
+    match health.status {
+        HealthCheckStatus::Operational => info!("Breez service status: Operational"),
+        HealthCheckStatus::Maintenance => info!("Breez service status: Maintenance"),
+        HealthCheckStatus::ServiceDisruption => {
+            info!("Breez service status: ServiceDisruption")
+        }
+    }
+}
+
+/// Runs a command against an already-connected SDK instance. Used both by the single-shot
+/// dispatch in `main` and by the interactive REPL, which share the one `Arc<BreezServices>`
+/// across many commands instead of reconnecting for each one.
+async fn dispatch(sdk: &Arc<BreezServices>, command: &Commands, format: OutputFormat) {
+    match command {
+        Commands::GenerateMnemonic => dispatch_mnemonic(),
+        Commands::HealthCheck => dispatch_health_check().await,
+        Commands::Interactive => {
+            info!("Already in interactive mode.");
+        }
+        Commands::NodeInfo => {
+            let node_info = sdk.node_info().unwrap();
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&node_info).unwrap());
+            } else {
+                info!("Node ID: {:?}", node_info.id);
+                info!("Spendable Amount: {:?}", node_info.max_payable_msat);
+            }
+        }
         Commands::ReceivePayment {
             amount_sats,
             description,
         } => {
-            let sdk = connect().await;
-            let invoice = receive_payment(&sdk, amount_sats, description).await;
-            info!("Invoice created: {}", invoice.ln_invoice.bolt11);
-            info!(
-                "Expected opening fee (msat): {:?}",
-                invoice.opening_fee_msat
-            );
-            info!("Waiting for invoice to be paid...");
-            pause();
+            let invoice = receive_payment(sdk, amount_sats, description).await;
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&invoice).unwrap());
+            } else {
+                info!("Invoice created: {}", invoice.ln_invoice.bolt11);
+                info!(
+                    "Expected opening fee (msat): {:?}",
+                    invoice.opening_fee_msat
+                );
+                info!("Waiting for invoice to be paid...");
+            }
         }
         Commands::LnUrlWithdraw { lnurl } => {
-            let sdk = connect().await;
-            lnurl_withdraw(&sdk, &lnurl).await;
-            pause();
+            let outcome = lnurl_withdraw(sdk, lnurl).await;
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&outcome).unwrap());
+            }
         }
-        Commands::LnUrlPay { lnurl } => {
-            let sdk = connect().await;
-            send_payment(&sdk, &lnurl).await;
-            pause();
+        Commands::LnUrlPay { lnurl, trampoline } => {
+            let payment = send_payment(sdk, lnurl, *trampoline).await;
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&payment).unwrap());
+            }
+        }
+        Commands::SendPayment { bolt11 } => {
+            let payment = send_payment(sdk, bolt11, true).await;
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&payment).unwrap());
+            } else {
+                info!("Payment sent: {:?}", payment.id);
+            }
         }
         Commands::ListPayments => {
-            let sdk = connect().await;
-            let payments = list_payments(&sdk).await;
-            dbg!("{:?}", payments);
-            pause();
+            let payments = list_payments(sdk).await;
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&payments).unwrap());
+            } else {
+                info!("{:?}", payments);
+            }
         }
         Commands::ChatGPT { prompt } => {
-            let sdk = connect().await;
-
             let url = "http://178.21.114.20:8000/openai/v1/chat/completions";
-            info!("Calling http 402 API without a token.");
-
-            let client = reqwest::ClientBuilder::new().build().unwrap();
-            let req = &GptRequest {
+            let req = GptRequest {
                 model: String::from("gpt-3.5-turbo"),
                 messages: vec![GptMessage {
                     role: String::from("user"),
                     content: prompt.clone(),
                 }],
             };
-            let mut resp = client.post(url).json(&req).send().await.unwrap();
-            info!("Response status is {}", resp.status());
-            let l402header = resp
-                .headers()
-                .get("WWW-Authenticate")
-                .expect("server did not return WWW-Authenticate header in 402 response.")
-                .to_str()
-                .unwrap();
-
-            info!("Got WWW-Authenticate header: {}", l402header);
-            let re = regex::Regex::new(
-                r#"^L402 (token|macaroon)=\"(?<token>.*)\", invoice=\"(?<invoice>.*)\""#,
-            )
-            .unwrap();
-            let caps = re
-                .captures(l402header)
-                .expect("WWW-Authenticate header is not a valid L402");
-            let token = caps["token"].to_string();
-            let invoice = caps["invoice"].to_string();
-            info!(
-                "Got lightning invoice to get access to the API: {}",
-                invoice
-            );
-
-            info!(
-                "Paying lightning invoice to get access to the API: {}",
-                invoice
-            );
-            let payresult = sdk.send_payment(invoice, None).await.unwrap();
-            let lnpayresult = match payresult.details {
-                breez_sdk_core::PaymentDetails::Ln { data } => data,
-                _ => unreachable!(),
-            };
-
-            let header = format!("L402 {}:{}", token, lnpayresult.payment_preimage);
-            info!(
-                "Calling http 402 api again, now with header Authorization {}",
-                header
-            );
-            resp = client
-                .post(url)
-                .header("Authorization", header)
-                .json(&req)
-                .send()
-                .await
-                .unwrap();
+            let body = serde_json::to_value(&req).unwrap();
 
-            let status = resp.status();
-            info!("Got Response. Status {}", status);
-            let text = resp.text().await.unwrap();
+            let text = l402::pay_and_fetch(sdk, url, "POST", &body).await.unwrap();
             info!("{}", text);
         }
-    };
+        Commands::Request { url, method, body } => {
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            let text = l402::pay_and_fetch(sdk, url, method, &body).await.unwrap();
+            info!("{}", text);
+        }
+    }
+}
+
+/// A single REPL line is parsed with the same `Commands` subcommand parser as the CLI itself,
+/// just without the binary name or the global `--verbose`/`--json` flags.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct ReplCommand {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+async fn run_interactive(sdk: &Arc<BreezServices>, format: OutputFormat) {
+    info!("Entering interactive mode. Type a command (e.g. 'nodeinfo'), or 'exit' to quit.");
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "exit" || line == "quit" {
+            break;
+        }
+
+        let args = match split_shell_words(line) {
+            Ok(args) => args,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+        let repl = match ReplCommand::try_parse_from(args) {
+            Ok(repl) => repl,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+
+        dispatch(sdk, &repl.command, format).await;
+    }
+}
+
+/// Splits a REPL line into shell-like words, respecting single and double quotes so that a
+/// quoted argument (an invoice description, a JSON request body, ...) can contain spaces.
+fn split_shell_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err("unterminated quote in command line".to_string());
+    }
+
+    if in_word {
+        words.push(current);
+    }
+
+    Ok(words)
 }
 
 #[derive(Parser)]
@@ -151,10 +258,21 @@ struct Cli {
     #[arg(short, long, action)]
     verbose: bool,
 
+    /// Print the results of node-info, receive-payment, send-payment, ln-url-pay, ln-url-withdraw
+    /// and list-payments as JSON on stdout; the other commands keep their human-readable log lines.
+    #[arg(long, action)]
+    json: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     #[clap(alias = "mnemonic")]
@@ -181,16 +299,46 @@ enum Commands {
     LnUrlPay {
         #[clap(long, short)]
         lnurl: String,
+
+        /// Route the payment through a trampoline node instead of computing the full route locally.
+        #[clap(long, action)]
+        trampoline: bool,
+    },
+
+    #[clap(alias = "sendpayment")]
+    SendPayment {
+        #[clap(long, short)]
+        bolt11: String,
     },
 
     #[clap(alias = "listpayments")]
     ListPayments,
 
+    #[clap(alias = "healthcheck")]
+    HealthCheck,
+
     #[clap(alias = "chatgpt")]
     ChatGPT {
         #[clap(long, short)]
         prompt: String,
     },
+
+    #[clap(alias = "request")]
+    Request {
+        #[clap(long, short)]
+        url: String,
+
+        /// HTTP method to use, e.g. GET or POST.
+        #[clap(long, short, default_value = "POST")]
+        method: String,
+
+        /// Request body, sent as JSON.
+        #[clap(long, short, default_value = "{}")]
+        body: String,
+    },
+
+    #[clap(alias = "repl")]
+    Interactive,
 }
 
 fn get_env_var(name: &str) -> Result<String, String> {
@@ -206,6 +354,29 @@ fn get_env_var(name: &str) -> Result<String, String> {
     Ok(v)
 }
 
+/// Loads a developer key/cert pair from `GREENLIGHT_DEVELOPER_KEY`/`GREENLIGHT_DEVELOPER_CERT`,
+/// returning `None` if neither is set. Each variable may hold either the credential itself or
+/// the path to a file containing it.
+fn get_partner_credentials() -> Option<GreenlightCredentials> {
+    let key = get_env_var("GREENLIGHT_DEVELOPER_KEY");
+    let cert = get_env_var("GREENLIGHT_DEVELOPER_CERT");
+
+    match (key, cert) {
+        (Ok(key), Ok(cert)) => Some(GreenlightCredentials {
+            developer_key: read_credential(&key),
+            developer_cert: read_credential(&cert),
+        }),
+        (Err(_), Err(_)) => None,
+        _ => panic!(
+            "GREENLIGHT_DEVELOPER_KEY and GREENLIGHT_DEVELOPER_CERT must both be set to use partner credentials"
+        ),
+    }
+}
+
+fn read_credential(value: &str) -> Vec<u8> {
+    fs::read(value).unwrap_or_else(|_| value.as_bytes().to_vec())
+}
+
 fn pause() {
     let mut stdin = io::stdin();
     let mut stdout = io::stdout();
@@ -257,15 +428,30 @@ async fn connect() -> Arc<BreezServices> {
     let mnemonic_str = get_env_var("MNEMONIC").unwrap();
     let mnemonic = Mnemonic::from_str(&mnemonic_str).unwrap();
     let seed = mnemonic.to_seed("");
-    let invite_code = Some(get_env_var("GREENLIGHT_INVITE_CODE").unwrap()).into();
     let api_key = get_env_var("BREEZ_API_KEY").unwrap().into();
 
+    let partner_credentials = get_partner_credentials();
+    let invite_code = match &partner_credentials {
+        Some(_) => {
+            if get_env_var("GREENLIGHT_INVITE_CODE").is_ok() {
+                panic!(
+                    "Set either GREENLIGHT_INVITE_CODE or GREENLIGHT_DEVELOPER_KEY/GREENLIGHT_DEVELOPER_CERT, not both"
+                );
+            }
+            None
+        }
+        None => Some(get_env_var("GREENLIGHT_INVITE_CODE").expect(
+            "Set either GREENLIGHT_INVITE_CODE or GREENLIGHT_DEVELOPER_KEY/GREENLIGHT_DEVELOPER_CERT",
+        )),
+    }
+    .into();
+
     let mut config = BreezServices::default_config(
         EnvironmentType::Production,
         api_key,
         breez_sdk_core::NodeConfig::Greenlight {
             config: GreenlightNodeConfig {
-                partner_credentials: None,
+                partner_credentials,
                 invite_code,
             },
         },
@@ -298,7 +484,7 @@ async fn receive_payment(
     .unwrap()
 }
 
-async fn lnurl_withdraw(sdk: &Arc<BreezServices>, lnurl: &str) {
+async fn lnurl_withdraw(sdk: &Arc<BreezServices>, lnurl: &str) -> Option<LnUrlCallbackStatus> {
     let lsp_id = sdk.lsp_id().await.unwrap().unwrap();
     sdk.connect_lsp(lsp_id).await.unwrap();
 
@@ -306,15 +492,49 @@ async fn lnurl_withdraw(sdk: &Arc<BreezServices>, lnurl: &str) {
         let amount_msat = wd.max_withdrawable;
         let description = "Test withdraw".to_string();
 
-        let _ = sdk
-            .lnurl_withdraw(wd, amount_msat / 1000, Some(description))
-            .await
-            .unwrap();
+        return Some(
+            sdk.lnurl_withdraw(wd, amount_msat / 1000, Some(description))
+                .await
+                .unwrap(),
+        );
     }
+
+    None
 }
 
-async fn send_payment(sdk: &Arc<BreezServices>, bolt11: &str) -> Payment {
-    sdk.send_payment(bolt11.into(), None).await.unwrap()
+pub(crate) async fn send_payment(
+    sdk: &Arc<BreezServices>,
+    bolt11: &str,
+    use_trampoline: bool,
+) -> Payment {
+    let request = SendPaymentRequest {
+        bolt11: bolt11.to_string(),
+        amount_msat: None,
+        label: None,
+        use_trampoline,
+    };
+
+    match sdk.send_payment(request).await {
+        Ok(payment) => payment,
+        Err(err) => {
+            report_payment_failure(sdk, bolt11, &err.to_string()).await;
+            panic!("failed to send payment: {err}");
+        }
+    }
+}
+
+async fn report_payment_failure(sdk: &Arc<BreezServices>, bolt11: &str, error: &str) {
+    let node_state = sdk.node_info().ok();
+    let report = PaymentFailureReport {
+        node_state,
+        bolt11: bolt11.to_string(),
+        error: error.to_string(),
+    };
+
+    match sdk.report_payment_failure(report).await {
+        Ok(_) => info!("Reported payment failure to Breez support."),
+        Err(e) => info!("Failed to report payment failure to Breez support: {}", e),
+    }
 }
 
 async fn list_payments(sdk: &Arc<BreezServices>) -> Vec<Payment> {